@@ -4,11 +4,19 @@ use clap::Parser;
 use cpal::traits::StreamTrait;
 use cpal::traits::{DeviceTrait, HostTrait};
 use ringbuf::RingBuffer;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 #[derive(Parser, Debug)]
 #[command(version, about = "CPAL beep example", long_about = None)]
 struct Opt {
-    /// The audio device to use
+    /// The audio host to use (e.g. "jack", "asio", "wasapi"). Defaults to the
+    /// platform's default host.
+    #[arg(long, default_value_t = String::from("default"))]
+    host: String,
+
+    /// The audio device to use. Pass "list" to print every device on the
+    /// selected host along with its default config.
     #[arg(short, long, default_value_t = String::from("default"))]
     output_device: String,
 
@@ -17,12 +25,64 @@ struct Opt {
 
     #[arg(short, long, default_value_t = 150f32)]
     latency: f32,
+
+    /// Write the captured input to a WAV file at this path while monitoring
+    #[arg(long)]
+    record: Option<String>,
+
+    /// Treat `--latency` as a starting point and grow/shrink the ring buffer
+    /// based on observed underrun/overrun rates instead of a fixed size.
+    #[arg(long)]
+    adaptive: bool,
+
+    /// A DSP effect to apply to the monitoring path: `gain:<db>`,
+    /// `highpass:<hz>`, `lowpass:<hz>`, or `delay:<ms>:<feedback>`. May be
+    /// given multiple times to build a chain, applied in order.
+    #[arg(long = "effect")]
+    effects: Vec<String>,
+}
+
+fn host_from_opt(opt: &Opt) -> anyhow::Result<cpal::Host> {
+    if opt.host == "default" {
+        return Ok(cpal::default_host());
+    }
+    let host_id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(&opt.host))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "host `{}` not available; available hosts: {:?}",
+                opt.host,
+                cpal::available_hosts()
+            )
+        })?;
+    Ok(cpal::host_from_id(host_id)?)
+}
+
+fn list_devices(host: &cpal::Host) -> anyhow::Result<()> {
+    println!("Devices on host `{}`:", host.id().name());
+    println!("Input devices:");
+    for device in host.input_devices()? {
+        let config = device.default_input_config();
+        println!("  {} - default config: {:?}", device.name()?, config);
+    }
+    println!("Output devices:");
+    for device in host.output_devices()? {
+        let config = device.default_output_config();
+        println!("  {} - default config: {:?}", device.name()?, config);
+    }
+    Ok(())
 }
 
 fn main() -> anyhow::Result<()> {
     let opt = Opt::parse();
 
-    let host = cpal::default_host();
+    let host = host_from_opt(&opt)?;
+    println!("Using host: {}", host.id().name());
+
+    if opt.output_device == "list" || opt.input_device == "list" {
+        return list_devices(&host);
+    }
 
     let output_device = if opt.output_device == "default" {
         host.default_output_device()
@@ -44,58 +104,684 @@ fn main() -> anyhow::Result<()> {
     .expect("failed to find output device");
     println!("Input device: {}", input_device.name().unwrap());
 
-    let config: cpal::StreamConfig = input_device.default_input_config().unwrap().into();
-    println!("Default config: {:?}", config);
+    let input_config = input_device.default_input_config().unwrap();
+    let output_config = output_device.default_output_config().unwrap();
+    println!("Default input config: {:?}", input_config);
+    println!("Default output config: {:?}", output_config);
+
+    // The device's native sample format dictates which callback type cpal
+    // will hand us; dispatch into a generic runner instead of forcing f32,
+    // which panics on devices that only expose integer PCM.
+    match input_config.sample_format() {
+        cpal::SampleFormat::F32 => run::<f32>(
+            &opt,
+            &input_device,
+            &output_device,
+            input_config.into(),
+            output_config.into(),
+        ),
+        cpal::SampleFormat::I16 => run::<i16>(
+            &opt,
+            &input_device,
+            &output_device,
+            input_config.into(),
+            output_config.into(),
+        ),
+        cpal::SampleFormat::U16 => run::<u16>(
+            &opt,
+            &input_device,
+            &output_device,
+            input_config.into(),
+            output_config.into(),
+        ),
+    }
+}
+
+/// Linearly resamples a mono/multi-channel stream pulled frame-by-frame from
+/// the ring buffer, bridging input and output devices that run at different
+/// sample rates and/or channel counts.
+struct Resampler {
+    in_channels: usize,
+    out_channels: usize,
+    ratio: f64,
+    pos: f64,
+    consumed_frames: u64,
+    prev_frame: Vec<f32>,
+    cur_frame: Vec<f32>,
+}
+
+impl Resampler {
+    fn new(in_channels: usize, out_channels: usize, in_rate: u32, out_rate: u32) -> Self {
+        Resampler {
+            in_channels,
+            out_channels,
+            ratio: in_rate as f64 / out_rate as f64,
+            pos: 0.0,
+            consumed_frames: 0,
+            prev_frame: vec![0.0; in_channels],
+            cur_frame: vec![0.0; in_channels],
+        }
+    }
+
+    /// Writes the next output frame into `out_frame`, pulling whole input
+    /// frames from `ring` as `pos` crosses integer boundaries. Returns `true`
+    /// if the ring ran dry at any point while pulling.
+    fn next_frame(&mut self, ring: &mut dyn RingConsumer, out_frame: &mut [f32]) -> bool {
+        let mut starved = false;
+        // `cur_frame` must land on `ceil(pos)` and `prev_frame` on `floor(pos)`
+        // so the interpolation below matches `s[floor]*(1-frac) + s[ceil]*frac`;
+        // looping to `pos as u64` (i.e. `floor(pos)`) would leave `cur_frame`
+        // one frame short and add a constant frame of latency.
+        let target_frame = self.pos.ceil() as u64;
+        while self.consumed_frames <= target_frame {
+            self.prev_frame.copy_from_slice(&self.cur_frame);
+            for sample in self.cur_frame.iter_mut() {
+                *sample = match ring.pop() {
+                    Some(s) => s,
+                    None => {
+                        starved = true;
+                        0.0
+                    }
+                };
+            }
+            self.consumed_frames += 1;
+        }
+
+        let frac = (self.pos.fract()) as f32;
+        for (ch, out_sample) in out_frame.iter_mut().enumerate().take(self.out_channels) {
+            let in_ch = ch.min(self.in_channels - 1);
+            // At an exact integer `pos` (guaranteed on the first output
+            // frame, where `pos == 0.0`), `floor(pos) == ceil(pos)` so the
+            // spec wants `s[floor(pos)]` exactly, i.e. `cur_frame`;
+            // `prev_frame` still holds the frame *before* it (zero-initialized
+            // on the first call), so interpolating would pull in silence.
+            *out_sample = if frac == 0.0 {
+                self.cur_frame[in_ch]
+            } else {
+                self.prev_frame[in_ch] * (1.0 - frac) + self.cur_frame[in_ch] * frac
+            };
+        }
+
+        self.pos += self.ratio;
+        starved
+    }
+}
+
+/// One endpoint of the feedback ring as an audio callback sees it: push a
+/// sample in (the input side) or pop one out (the output side). Two
+/// implementations back this: a plain lock-free `ringbuf` endpoint for the
+/// default fixed-latency path, and `AdaptiveRing`'s mutex-guarded endpoints
+/// for `--adaptive`, where a control thread needs to swap in a
+/// differently-sized buffer underneath the callbacks. Keeping them separate
+/// means only `--adaptive` users pay for the lock.
+trait RingProducer: Send {
+    /// Returns `false` (an overrun) if the ring was full.
+    fn push(&mut self, sample: f32) -> bool;
+}
+
+trait RingConsumer: Send {
+    fn pop(&mut self) -> Option<f32>;
+}
+
+/// The producer/consumer endpoints `run` wires up for the feedback ring,
+/// plus the adaptive-latency control thread when `--adaptive` spawned one.
+type FeedbackRing = (
+    Box<dyn RingProducer>,
+    Box<dyn RingConsumer>,
+    Option<std::thread::JoinHandle<()>>,
+);
+
+impl RingProducer for ringbuf::Producer<f32> {
+    fn push(&mut self, sample: f32) -> bool {
+        ringbuf::Producer::push(self, sample).is_ok()
+    }
+}
+
+impl RingConsumer for ringbuf::Consumer<f32> {
+    fn pop(&mut self) -> Option<f32> {
+        ringbuf::Consumer::pop(self)
+    }
+}
+
+/// Splits a fresh ring buffer and primes it half full of silence, so the
+/// output side has something to pop before the input side has produced
+/// anything.
+fn primed_ring_split(capacity_samples: usize) -> (ringbuf::Producer<f32>, ringbuf::Consumer<f32>) {
+    let ring = RingBuffer::new(capacity_samples);
+    let (mut producer, consumer) = ring.split();
+    for _ in 0..capacity_samples / 2 {
+        producer.push(0.0).ok();
+    }
+    (producer, consumer)
+}
+
+/// The feedback ring buffer used in `--adaptive` mode, plus the
+/// underrun/overrun counters and resize machinery the control thread needs.
+/// Producer and consumer live behind a mutex so the control thread can swap
+/// in a differently-sized buffer while the audio callbacks keep running.
+struct AdaptiveRing {
+    producer: Mutex<ringbuf::Producer<f32>>,
+    consumer: Mutex<ringbuf::Consumer<f32>>,
+    capacity: AtomicUsize,
+    underruns: AtomicUsize,
+    overruns: AtomicUsize,
+}
+
+impl AdaptiveRing {
+    fn new(capacity_samples: usize) -> Arc<Self> {
+        let (producer, consumer) = primed_ring_split(capacity_samples);
+        Arc::new(AdaptiveRing {
+            producer: Mutex::new(producer),
+            consumer: Mutex::new(consumer),
+            capacity: AtomicUsize::new(capacity_samples),
+            underruns: AtomicUsize::new(0),
+            overruns: AtomicUsize::new(0),
+        })
+    }
+
+    fn push(&self, sample: f32) -> bool {
+        if self.producer.lock().unwrap().push(sample).is_err() {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+            false
+        } else {
+            true
+        }
+    }
+
+    fn pop(&self) -> Option<f32> {
+        let sample = self.consumer.lock().unwrap().pop();
+        if sample.is_none() {
+            self.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+        sample
+    }
+
+    /// Reallocates at `new_capacity` and re-primes with silence. This
+    /// discards whatever was buffered, trading a brief glitch for headroom.
+    fn resize(&self, new_capacity: usize) {
+        let (producer, consumer) = primed_ring_split(new_capacity);
+        *self.producer.lock().unwrap() = producer;
+        *self.consumer.lock().unwrap() = consumer;
+        self.capacity.store(new_capacity, Ordering::Relaxed);
+    }
+}
+
+impl RingProducer for Arc<AdaptiveRing> {
+    fn push(&mut self, sample: f32) -> bool {
+        AdaptiveRing::push(self, sample)
+    }
+}
+
+impl RingConsumer for Arc<AdaptiveRing> {
+    fn pop(&mut self) -> Option<f32> {
+        AdaptiveRing::pop(self)
+    }
+}
+
+/// Maps a cpal sample type onto the WAV header fields and sample encoding
+/// `hound` needs, so the recorded file matches the negotiated stream format.
+trait WavSample: cpal::Sample {
+    const BITS_PER_SAMPLE: u16;
+    const SAMPLE_FORMAT: hound::SampleFormat;
+
+    fn write<W: std::io::Write + std::io::Seek>(
+        writer: &mut hound::WavWriter<W>,
+        sample: f32,
+    ) -> hound::Result<()>;
+}
+
+impl WavSample for f32 {
+    const BITS_PER_SAMPLE: u16 = 32;
+    const SAMPLE_FORMAT: hound::SampleFormat = hound::SampleFormat::Float;
+
+    fn write<W: std::io::Write + std::io::Seek>(
+        writer: &mut hound::WavWriter<W>,
+        sample: f32,
+    ) -> hound::Result<()> {
+        writer.write_sample(sample)
+    }
+}
+
+impl WavSample for i16 {
+    const BITS_PER_SAMPLE: u16 = 16;
+    const SAMPLE_FORMAT: hound::SampleFormat = hound::SampleFormat::Int;
+
+    fn write<W: std::io::Write + std::io::Seek>(
+        writer: &mut hound::WavWriter<W>,
+        sample: f32,
+    ) -> hound::Result<()> {
+        writer.write_sample(<i16 as cpal::Sample>::from(&sample))
+    }
+}
+
+impl WavSample for u16 {
+    // hound has no unsigned PCM sample format, so u16-native devices are
+    // recorded as signed 16-bit, matching the bit depth if not the signedness.
+    const BITS_PER_SAMPLE: u16 = 16;
+    const SAMPLE_FORMAT: hound::SampleFormat = hound::SampleFormat::Int;
+
+    fn write<W: std::io::Write + std::io::Seek>(
+        writer: &mut hound::WavWriter<W>,
+        sample: f32,
+    ) -> hound::Result<()> {
+        writer.write_sample(<i16 as cpal::Sample>::from(&sample))
+    }
+}
+
+/// A single stage in the monitoring effects chain, with its own per-channel
+/// state. Chains are built once per channel so a filter's history taps or a
+/// delay's buffer never mix samples across channels.
+trait Effect: Send {
+    fn process(&mut self, sample: f32) -> f32;
+}
+
+struct Gain {
+    multiplier: f32,
+}
+
+impl Effect for Gain {
+    fn process(&mut self, sample: f32) -> f32 {
+        sample * self.multiplier
+    }
+}
+
+/// An RBJ "Audio EQ Cookbook" biquad, run as Direct Form I with two sample
+/// of input/output history.
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    fn lowpass(hz: f32, sample_rate: f32) -> Self {
+        let w0 = 2.0 * std::f32::consts::PI * hz / sample_rate;
+        let alpha = w0.sin() / (2.0 * std::f32::consts::FRAC_1_SQRT_2);
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b0: ((1.0 - cos_w0) / 2.0) / a0,
+            b1: (1.0 - cos_w0) / a0,
+            b2: ((1.0 - cos_w0) / 2.0) / a0,
+            a1: (-2.0 * cos_w0) / a0,
+            a2: (1.0 - alpha) / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn highpass(hz: f32, sample_rate: f32) -> Self {
+        let w0 = 2.0 * std::f32::consts::PI * hz / sample_rate;
+        let alpha = w0.sin() / (2.0 * std::f32::consts::FRAC_1_SQRT_2);
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b0: ((1.0 + cos_w0) / 2.0) / a0,
+            b1: (-(1.0 + cos_w0)) / a0,
+            b2: ((1.0 + cos_w0) / 2.0) / a0,
+            a1: (-2.0 * cos_w0) / a0,
+            a2: (1.0 - alpha) / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+}
+
+impl Effect for Biquad {
+    fn process(&mut self, x0: f32) -> f32 {
+        let y0 =
+            self.b0 * x0 + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x0;
+        self.y2 = self.y1;
+        self.y1 = y0;
+        y0
+    }
+}
+
+/// A feedback delay line: each incoming sample is mixed with whatever is
+/// `ms` milliseconds behind it, and the incoming sample plus the delayed one
+/// scaled by `feedback` is written back into the ring for the next pass.
+struct Delay {
+    buffer: Vec<f32>,
+    pos: usize,
+    feedback: f32,
+}
+
+impl Effect for Delay {
+    fn process(&mut self, sample: f32) -> f32 {
+        let delayed = self.buffer[self.pos];
+        let out = sample + delayed;
+        self.buffer[self.pos] = sample + delayed * self.feedback;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        out
+    }
+}
+
+/// A parsed `--effect` flag, kept separate from the built `Effect` so the
+/// same spec can be instantiated once per channel.
+enum EffectSpec {
+    Gain { db: f32 },
+    Highpass { hz: f32 },
+    Lowpass { hz: f32 },
+    Delay { ms: f32, feedback: f32 },
+}
+
+impl EffectSpec {
+    fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut parts = spec.split(':');
+        let kind = parts
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("empty --effect value"))?;
+        match kind {
+            "gain" => {
+                let db: f32 = parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("gain effect needs a `:<db>` argument"))?
+                    .parse()?;
+                Ok(EffectSpec::Gain { db })
+            }
+            "highpass" => {
+                let hz: f32 = parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("highpass effect needs a `:<hz>` argument"))?
+                    .parse()?;
+                Ok(EffectSpec::Highpass { hz })
+            }
+            "lowpass" => {
+                let hz: f32 = parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("lowpass effect needs a `:<hz>` argument"))?
+                    .parse()?;
+                Ok(EffectSpec::Lowpass { hz })
+            }
+            "delay" => {
+                let ms: f32 = parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("delay effect needs `:<ms>:<feedback>`"))?
+                    .parse()?;
+                let feedback: f32 = parts
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("delay effect needs `:<ms>:<feedback>`"))?
+                    .parse()?;
+                Ok(EffectSpec::Delay { ms, feedback })
+            }
+            other => Err(anyhow::anyhow!(
+                "unknown effect `{}`; expected gain, highpass, lowpass, or delay",
+                other
+            )),
+        }
+    }
+
+    fn build(&self, sample_rate: f32) -> Box<dyn Effect> {
+        match *self {
+            EffectSpec::Gain { db } => Box::new(Gain {
+                multiplier: 10f32.powf(db / 20.0),
+            }),
+            EffectSpec::Highpass { hz } => Box::new(Biquad::highpass(hz, sample_rate)),
+            EffectSpec::Lowpass { hz } => Box::new(Biquad::lowpass(hz, sample_rate)),
+            EffectSpec::Delay { ms, feedback } => {
+                let len = ((ms / 1_000.0) * sample_rate).max(1.0) as usize;
+                Box::new(Delay {
+                    buffer: vec![0.0; len],
+                    pos: 0,
+                    feedback,
+                })
+            }
+        }
+    }
+}
+
+/// The output stream callback `run` builds: either a resampling/remapping
+/// closure or a plain pass-through one, boxed so both arms fit one binding.
+type OutputDataFn<T> = Box<dyn FnMut(&mut [T], &cpal::OutputCallbackInfo) + Send>;
+
+fn run<T>(
+    opt: &Opt,
+    input_device: &cpal::Device,
+    output_device: &cpal::Device,
+    input_config: cpal::StreamConfig,
+    output_config: cpal::StreamConfig,
+) -> anyhow::Result<()>
+where
+    T: cpal::Sample + WavSample + 'static,
+{
+    println!("Selected sample format: {}", std::any::type_name::<T>());
+    println!("Input config: {:?}", input_config);
+    println!("Output config: {:?}", output_config);
 
     // Create a delay in case the input and output devices aren't synced.
-    let latency_frames = (opt.latency / 1_000.0) * config.sample_rate.0 as f32;
-    let latency_samples = latency_frames as usize * config.channels as usize;
+    let latency_frames = (opt.latency / 1_000.0) * input_config.sample_rate.0 as f32;
+    let latency_samples = latency_frames as usize * input_config.channels as usize;
+
+    // The ring buffer always stores f32 so the feedback path is agnostic to
+    // the device's native sample type; the input/output callbacks convert
+    // at the boundary via `Sample::to_f32`/`Sample::from`. Without
+    // `--adaptive` this is a plain lock-free `ringbuf` pair owned directly by
+    // the callbacks; with it, both sides go through `AdaptiveRing` so a
+    // control thread can grow or shrink the buffer underneath them.
+    let samples_per_channel_ms = input_config.sample_rate.0 as f32 / 1_000.0;
+    let min_capacity = (input_config.channels as f32 * samples_per_channel_ms * 10.0) as usize;
+
+    let adaptive_running = Arc::new(AtomicBool::new(true));
+    let (mut input_ring, mut output_ring, adaptive_thread): FeedbackRing = if opt.adaptive {
+        let ring = AdaptiveRing::new(latency_samples * 2);
+        let adaptive_thread = {
+            let ring = ring.clone();
+            let running = adaptive_running.clone();
+            let channels = input_config.channels as f32;
+            std::thread::spawn(move || {
+                let mut quiet_windows = 0u32;
+                while running.load(Ordering::Acquire) {
+                    std::thread::sleep(std::time::Duration::from_millis(200));
+                    let underruns = ring.underruns.swap(0, Ordering::Relaxed);
+                    let overruns = ring.overruns.swap(0, Ordering::Relaxed);
+                    let capacity = ring.capacity.load(Ordering::Relaxed);
+                    if underruns > 0 {
+                        quiet_windows = 0;
+                        let new_capacity = (capacity as f32 * 1.5) as usize;
+                        ring.resize(new_capacity);
+                        println!(
+                            "Adaptive latency: {} underruns, growing to {:.1} ms",
+                            underruns,
+                            // The ring is primed half full, so latency is half its capacity.
+                            new_capacity as f32 / 2.0 / channels / samples_per_channel_ms
+                        );
+                    } else if overruns == 0 {
+                        quiet_windows += 1;
+                        if quiet_windows >= 5 && capacity > min_capacity {
+                            let new_capacity = ((capacity as f32 * 0.8) as usize).max(min_capacity);
+                            ring.resize(new_capacity);
+                            println!(
+                                "Adaptive latency: stable for {} windows, shrinking to {:.1} ms",
+                                quiet_windows,
+                                new_capacity as f32 / 2.0 / channels / samples_per_channel_ms
+                            );
+                            quiet_windows = 0;
+                        }
+                    } else {
+                        quiet_windows = 0;
+                    }
+                }
+            })
+        };
+        (
+            Box::new(ring.clone()) as Box<dyn RingProducer>,
+            Box::new(ring) as Box<dyn RingConsumer>,
+            Some(adaptive_thread),
+        )
+    } else {
+        let (producer, consumer) = primed_ring_split(latency_samples * 2);
+        (
+            Box::new(producer) as Box<dyn RingProducer>,
+            Box::new(consumer) as Box<dyn RingConsumer>,
+            None,
+        )
+    };
 
-    let ring = RingBuffer::new(latency_samples * 2);
-    let (mut producer, mut consumer) = ring.split();
+    // The recording path gets its own ring buffer so the real-time input
+    // callback only ever enqueues; a background thread drains it to disk,
+    // keeping file I/O off the audio thread.
+    let recording = Arc::new(AtomicBool::new(opt.record.is_some()));
+    let mut record_producer = None;
+    let mut record_thread = None;
+    if let Some(path) = opt.record.clone() {
+        let spec = hound::WavSpec {
+            channels: input_config.channels,
+            sample_rate: input_config.sample_rate.0,
+            bits_per_sample: T::BITS_PER_SAMPLE,
+            sample_format: T::SAMPLE_FORMAT,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec)?;
+        // Sized well beyond the monitoring ring: a brief disk stall should
+        // drain without corrupting the WAV, which a latency-sized buffer
+        // couldn't absorb.
+        let record_ring = RingBuffer::new(latency_samples * 20);
+        let (producer, mut consumer) = record_ring.split();
+        record_producer = Some(producer);
 
-    for _ in 0..latency_samples {
-        producer.push(0.0).unwrap();
+        let recording = recording.clone();
+        record_thread = Some(std::thread::spawn(move || {
+            while recording.load(Ordering::Acquire) || !consumer.is_empty() {
+                match consumer.pop() {
+                    Some(sample) => {
+                        if let Err(err) = T::write(&mut writer, sample) {
+                            eprintln!("failed to write sample to `{}`: {}", path, err);
+                        }
+                    }
+                    None => std::thread::sleep(std::time::Duration::from_millis(5)),
+                }
+            }
+            if let Err(err) = writer.finalize() {
+                eprintln!("failed to finalize `{}`: {}", path, err);
+            } else {
+                println!("Recording saved to `{}`.", path);
+            }
+        }));
     }
 
-    let input_data_fn = move |data: &[f32], _: &cpal::InputCallbackInfo| {
+    let input_data_fn = move |data: &[T], _: &cpal::InputCallbackInfo| {
         let mut output_fell_behind = false;
+        let mut recording_fell_behind = false;
         for &sample in data {
-            if producer.push(sample).is_err() {
+            let sample = sample.to_f32();
+            if !input_ring.push(sample) {
                 output_fell_behind = true;
             }
+            if let Some(record_producer) = record_producer.as_mut() {
+                if record_producer.push(sample).is_err() {
+                    recording_fell_behind = true;
+                }
+            }
         }
         if output_fell_behind {
             eprintln!("output stream fell behind: try increasing latency");
         }
+        if recording_fell_behind {
+            eprintln!("recording fell behind: dropped samples, try a faster disk or longer `--latency`");
+        }
     };
 
-    let output_data_fn = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-        let mut input_fell_behind = false;
-        for sample in data {
-            *sample = match consumer.pop() {
-                Some(s) => s,
-                None => {
-                    input_fell_behind = true;
-                    0.0
+    let in_channels = input_config.channels as usize;
+    let out_channels = output_config.channels as usize;
+    // A channel-count mismatch needs the same per-output-frame remapping as a
+    // sample-rate mismatch does, so route both through `Resampler` — it
+    // already handles a 1:1 rate ratio correctly, and duplicates/drops
+    // channels via `in_ch.min(self.in_channels - 1)` regardless of ratio.
+    let needs_resampling =
+        input_config.sample_rate != output_config.sample_rate || in_channels != out_channels;
+
+    let effect_specs = opt
+        .effects
+        .iter()
+        .map(|spec| EffectSpec::parse(spec))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // One chain per channel so a filter's history or a delay's buffer never
+    // bleeds state across channels.
+    let mut effect_chains: Vec<Vec<Box<dyn Effect>>> = (0..out_channels)
+        .map(|_| {
+            effect_specs
+                .iter()
+                .map(|spec| spec.build(output_config.sample_rate.0 as f32))
+                .collect()
+        })
+        .collect();
+
+    let output_data_fn: OutputDataFn<T> =
+        if needs_resampling {
+            println!(
+                "Input and output configs differ ({} Hz/{} ch vs {} Hz/{} ch); resampling.",
+                input_config.sample_rate.0,
+                in_channels,
+                output_config.sample_rate.0,
+                out_channels
+            );
+            let mut resampler = Resampler::new(
+                in_channels,
+                out_channels,
+                input_config.sample_rate.0,
+                output_config.sample_rate.0,
+            );
+            let mut out_frame = vec![0.0f32; out_channels];
+            Box::new(move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let mut input_fell_behind = false;
+                for frame in data.chunks_mut(out_channels) {
+                    if resampler.next_frame(&mut *output_ring, &mut out_frame) {
+                        input_fell_behind = true;
+                    }
+                    for (ch, (sample, &value)) in
+                        frame.iter_mut().zip(out_frame.iter()).enumerate()
+                    {
+                        let mut value = value;
+                        for effect in effect_chains[ch].iter_mut() {
+                            value = effect.process(value);
+                        }
+                        *sample = T::from(&value);
+                    }
                 }
-            };
-        }
-        if input_fell_behind {
-            eprintln!("input stream fell behind: try increasing latency");
-        }
-    };
+                if input_fell_behind {
+                    eprintln!("input stream fell behind: try increasing latency");
+                }
+            })
+        } else {
+            Box::new(move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let mut input_fell_behind = false;
+                for (ch, sample) in data.iter_mut().enumerate() {
+                    let mut value = match output_ring.pop() {
+                        Some(s) => s,
+                        None => {
+                            input_fell_behind = true;
+                            0.0
+                        }
+                    };
+                    for effect in effect_chains[ch % out_channels].iter_mut() {
+                        value = effect.process(value);
+                    }
+                    *sample = T::from(&value);
+                }
+                if input_fell_behind {
+                    eprintln!("input stream fell behind: try increasing latency");
+                }
+            })
+        };
 
     println!(
-        "Attempting to build both streams with f32 samples and `{:?}`.",
-        config
+        "Attempting to build both streams with {} samples.",
+        std::any::type_name::<T>(),
     );
-    let input_stream = input_device
-        .build_input_stream(&config, input_data_fn, err_fn)
-        .unwrap();
-    let output_stream = output_device
-        .build_output_stream(&config, output_data_fn, err_fn)
-        .unwrap();
+    let input_stream = input_device.build_input_stream(&input_config, input_data_fn, err_fn)?;
+    let output_stream =
+        output_device.build_output_stream(&output_config, output_data_fn, err_fn)?;
 
     println!("Successfully built streams.");
 
@@ -103,13 +789,24 @@ fn main() -> anyhow::Result<()> {
         "Starting the input and output streams with `{}` milliseconds of latency.",
         opt.latency
     );
-    input_stream.play().unwrap();
-    input_stream.play().unwrap();
+    input_stream.play()?;
+    output_stream.play()?;
 
     println!("Playing for 3 seconds... ");
     std::thread::sleep(std::time::Duration::from_secs(3));
     drop(input_stream);
     drop(output_stream);
+
+    if let Some(record_thread) = record_thread {
+        recording.store(false, Ordering::Release);
+        record_thread.join().expect("recording thread panicked");
+    }
+
+    if let Some(adaptive_thread) = adaptive_thread {
+        adaptive_running.store(false, Ordering::Release);
+        adaptive_thread.join().expect("adaptive latency thread panicked");
+    }
+
     println!("Done!");
 
     Ok(())
@@ -118,3 +815,103 @@ fn main() -> anyhow::Result<()> {
 fn err_fn(err: cpal::StreamError) {
     eprintln!("an error occurred on stream: {}", err);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// A `RingConsumer` over a fixed sequence of samples, so `Resampler`
+    /// tests don't need a real `ringbuf`/`AdaptiveRing`.
+    struct FakeConsumer(VecDeque<f32>);
+
+    impl FakeConsumer {
+        fn new(samples: impl IntoIterator<Item = f32>) -> Self {
+            FakeConsumer(samples.into_iter().collect())
+        }
+    }
+
+    impl RingConsumer for FakeConsumer {
+        fn pop(&mut self) -> Option<f32> {
+            self.0.pop_front()
+        }
+    }
+
+    #[test]
+    fn next_frame_at_pos_zero_returns_first_frame_not_silence() {
+        let mut resampler = Resampler::new(1, 1, 48_000, 44_100);
+        let mut ring = FakeConsumer::new([1.0, 2.0, 3.0]);
+        let mut out_frame = [0.0];
+        let starved = resampler.next_frame(&mut ring, &mut out_frame);
+        assert!(!starved);
+        assert_eq!(out_frame, [1.0]);
+    }
+
+    #[test]
+    fn next_frame_interpolates_between_bracketing_samples() {
+        // A 3:2 ratio (1.5) puts the second output frame's `pos` at 1.5, so
+        // it should land exactly halfway between the second and third
+        // input samples.
+        let mut resampler = Resampler::new(1, 1, 3, 2);
+        let mut ring = FakeConsumer::new([0.0, 10.0, 20.0]);
+        let mut out_frame = [0.0];
+        resampler.next_frame(&mut ring, &mut out_frame);
+        assert_eq!(out_frame, [0.0]);
+        resampler.next_frame(&mut ring, &mut out_frame);
+        assert_eq!(out_frame, [15.0]);
+    }
+
+    #[test]
+    fn next_frame_duplicates_channels_when_upmixing() {
+        let mut resampler = Resampler::new(1, 2, 1, 1);
+        let mut ring = FakeConsumer::new([7.0]);
+        let mut out_frame = [0.0, 0.0];
+        resampler.next_frame(&mut ring, &mut out_frame);
+        assert_eq!(out_frame, [7.0, 7.0]);
+    }
+
+    #[test]
+    fn next_frame_reports_starved_when_ring_runs_dry() {
+        let mut resampler = Resampler::new(1, 1, 1, 1);
+        let mut ring = FakeConsumer::new([]);
+        let mut out_frame = [0.0];
+        let starved = resampler.next_frame(&mut ring, &mut out_frame);
+        assert!(starved);
+    }
+
+    #[test]
+    fn effect_spec_parses_each_kind() {
+        assert!(matches!(
+            EffectSpec::parse("gain:-6").unwrap(),
+            EffectSpec::Gain { db } if db == -6.0
+        ));
+        assert!(matches!(
+            EffectSpec::parse("highpass:200").unwrap(),
+            EffectSpec::Highpass { hz } if hz == 200.0
+        ));
+        assert!(matches!(
+            EffectSpec::parse("lowpass:4000").unwrap(),
+            EffectSpec::Lowpass { hz } if hz == 4000.0
+        ));
+        assert!(matches!(
+            EffectSpec::parse("delay:250:0.3").unwrap(),
+            EffectSpec::Delay { ms, feedback } if ms == 250.0 && feedback == 0.3
+        ));
+    }
+
+    #[test]
+    fn effect_spec_rejects_unknown_kind() {
+        assert!(EffectSpec::parse("reverb:1").is_err());
+    }
+
+    #[test]
+    fn effect_spec_rejects_missing_argument() {
+        assert!(EffectSpec::parse("gain").is_err());
+        assert!(EffectSpec::parse("delay:250").is_err());
+    }
+
+    #[test]
+    fn effect_spec_rejects_unparseable_argument() {
+        assert!(EffectSpec::parse("gain:loud").is_err());
+    }
+}